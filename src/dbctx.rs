@@ -0,0 +1,663 @@
+//! `DbCtx` is the persistence boundary for the orchestrator: every build,
+//! instance, and spec assignment that needs to survive a restart goes
+//! through here instead of living only in the in-memory `AppState` map.
+//!
+//! Mirrors build-o-tron's split of "connection + queries" (`DbCtx`) from
+//! "SQL text" (`sql`): this module owns transactions and row mapping, the
+//! `sql` module owns the statements themselves.
+
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection};
+use tokio::sync::Mutex as AsyncMutex;
+
+use crate::sql;
+use crate::{Instance, TestStatus};
+
+pub struct DbCtx {
+    conn: AsyncMutex<Connection>,
+}
+
+impl DbCtx {
+    pub fn open(path: &str) -> rusqlite::Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(sql::CREATE_TABLES)?;
+        Ok(DbCtx {
+            conn: AsyncMutex::new(conn),
+        })
+    }
+
+    /// Ensures a build row (and its initial spec queue) exists, returning
+    /// its `created_on` timestamp. An already-known build is left as it is,
+    /// except that `specs` become its queue if it has neither queued specs
+    /// nor registered instances.
+    pub async fn ensure_build(
+        &self,
+        build_id: &str,
+        specs: &[String],
+    ) -> rusqlite::Result<DateTime<Utc>> {
+        let mut conn = self.conn.lock().await;
+        let tx = conn.transaction()?;
+
+        tx.execute(sql::INSERT_BUILD, params![build_id, Utc::now().to_rfc3339()])?;
+
+        let existing_specs: i64 = tx.query_row(
+            "SELECT COUNT(*) FROM remaining_specs WHERE build_id = ?1",
+            params![build_id],
+            |row| row.get(0),
+        )?;
+        let already_has_instances: i64 = tx.query_row(
+            sql::COUNT_INSTANCES_FOR_BUILD,
+            params![build_id],
+            |row| row.get(0),
+        )?;
+        if existing_specs == 0 && already_has_instances == 0 {
+            for (position, spec) in specs.iter().enumerate() {
+                tx.execute(
+                    sql::INSERT_REMAINING_SPEC,
+                    params![build_id, spec, position as i64],
+                )?;
+            }
+        }
+
+        let created_on: String =
+            tx.query_row(sql::SELECT_BUILD, params![build_id], |row| row.get(1))?;
+
+        tx.commit()?;
+        Ok(DateTime::parse_from_rfc3339(&created_on)
+            .unwrap()
+            .with_timezone(&Utc))
+    }
+
+    pub async fn upsert_instance(
+        &self,
+        build_id: &str,
+        instance_id: &str,
+        is_master: bool,
+    ) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().await;
+        conn.execute(
+            sql::UPSERT_INSTANCE,
+            params![
+                build_id,
+                instance_id,
+                is_master,
+                status_str(&TestStatus::Ongoing),
+                Utc::now().to_rfc3339()
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Updates `last_seen` for an instance. Called on every authenticated
+    /// request (directly via `/heartbeat`, or incidentally as a side
+    /// effect of any other endpoint) so the dead-instance sweep has an
+    /// accurate picture of who's still around.
+    pub async fn touch_instance(&self, build_id: &str, instance_id: &str) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().await;
+        conn.execute(
+            sql::TOUCH_INSTANCE,
+            params![build_id, instance_id, Utc::now().to_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    pub async fn instance_count(&self, build_id: &str) -> rusqlite::Result<i64> {
+        let conn = self.conn.lock().await;
+        conn.query_row(sql::COUNT_INSTANCES_FOR_BUILD, params![build_id], |row| {
+            row.get(0)
+        })
+    }
+
+    pub async fn load_instance(
+        &self,
+        build_id: &str,
+        instance_id: &str,
+    ) -> rusqlite::Result<Option<Instance>> {
+        let conn = self.conn.lock().await;
+        let row = conn
+            .query_row(sql::SELECT_INSTANCE, params![build_id, instance_id], |row| {
+                let is_master: bool = row.get(0)?;
+                let status: String = row.get(1)?;
+                Ok((is_master, status))
+            })
+            .ok();
+        let Some((is_master, status)) = row else {
+            return Ok(None);
+        };
+
+        let mut stmt = conn.prepare(sql::SELECT_INSTANCE_SPECS)?;
+        let test_list = stmt
+            .query_map(params![build_id, instance_id], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+
+        Ok(Some(Instance {
+            test_list,
+            test_status: parse_status(&status),
+            is_master,
+        }))
+    }
+
+    /// Pops the next spec off `instance_id`'s own LPT partition (see
+    /// `partition_specs_if_ready`) and records it as assigned, all inside
+    /// one transaction so a spec is never handed out twice. Falls back to
+    /// the build's unassigned `remaining_specs` pool, FIFO, when the
+    /// partition is empty — either because it hasn't been computed yet (not
+    /// enough instances have registered) or because a dead-instance requeue
+    /// dropped a spec there in the absence of another live instance (see
+    /// `requeue_dead_instances`). Returns `None` only once both are empty.
+    pub async fn take_next_spec(
+        &self,
+        build_id: &str,
+        instance_id: &str,
+    ) -> rusqlite::Result<Option<String>> {
+        let mut conn = self.conn.lock().await;
+        let tx = conn.transaction()?;
+
+        let pending: Option<(i64, String)> = tx
+            .query_row(
+                sql::SELECT_NEXT_PENDING_SPEC,
+                params![build_id, instance_id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .ok();
+
+        let spec = if let Some((rowid, spec)) = pending {
+            tx.execute(sql::DELETE_PENDING_SPEC_BY_ROWID, params![rowid])?;
+            Some(spec)
+        } else {
+            let remaining: Option<(i64, String)> = tx
+                .query_row(
+                    sql::SELECT_NEXT_REMAINING_SPEC,
+                    params![build_id],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .ok();
+            match remaining {
+                Some((rowid, spec)) => {
+                    tx.execute(sql::DELETE_REMAINING_SPEC_BY_ROWID, params![rowid])?;
+                    Some(spec)
+                }
+                None => None,
+            }
+        };
+
+        let Some(spec) = spec else {
+            tx.commit()?;
+            return Ok(None);
+        };
+
+        tx.execute(
+            sql::INSERT_INSTANCE_SPEC,
+            params![build_id, instance_id, spec],
+        )?;
+        tx.commit()?;
+        Ok(Some(spec))
+    }
+
+    /// Longest-Processing-Time partitioning: once `expected_instance_count`
+    /// instances have registered for a build (and the unassigned pool
+    /// hasn't already been partitioned), sort the remaining specs by
+    /// estimated duration descending and greedily assign each to the
+    /// currently least-loaded instance. Specs with no recorded history use
+    /// the mean of all recorded durations, or `default_duration_ms` when
+    /// nothing has been recorded yet. A no-op (returning `false`) until the
+    /// expected instance count is reached.
+    pub async fn partition_specs_if_ready(
+        &self,
+        build_id: &str,
+        expected_instance_count: i64,
+        default_duration_ms: u64,
+    ) -> rusqlite::Result<bool> {
+        let mut conn = self.conn.lock().await;
+        let tx = conn.transaction()?;
+
+        let instance_count: i64 = tx.query_row(
+            sql::COUNT_INSTANCES_FOR_BUILD,
+            params![build_id],
+            |row| row.get(0),
+        )?;
+        let unassigned_count: i64 = tx.query_row(
+            sql::COUNT_REMAINING_SPECS_FOR_BUILD,
+            params![build_id],
+            |row| row.get(0),
+        )?;
+        if instance_count < expected_instance_count || unassigned_count == 0 {
+            tx.commit()?;
+            return Ok(false);
+        }
+
+        let specs: Vec<String> = {
+            let mut stmt = tx.prepare(sql::SELECT_REMAINING_SPECS_FOR_BUILD)?;
+            stmt.query_map(params![build_id], |row| row.get(0))?
+                .collect::<rusqlite::Result<Vec<String>>>()?
+        };
+        let instance_ids: Vec<String> = {
+            let mut stmt = tx.prepare(sql::SELECT_INSTANCE_IDS_FOR_BUILD)?;
+            stmt.query_map(params![build_id], |row| row.get(0))?
+                .collect::<rusqlite::Result<Vec<String>>>()?
+        };
+
+        let fallback_duration: f64 = tx
+            .query_row(sql::SELECT_AVG_DURATION_OVERALL, [], |row| {
+                row.get::<_, Option<f64>>(0)
+            })
+            .unwrap_or(None)
+            .unwrap_or(default_duration_ms as f64);
+
+        let mut durations: Vec<(String, f64)> = Vec::with_capacity(specs.len());
+        for spec in specs {
+            let duration: f64 = tx
+                .query_row(sql::SELECT_AVG_DURATION_FOR_SPEC, params![spec], |row| {
+                    row.get::<_, Option<f64>>(0)
+                })
+                .unwrap_or(None)
+                .unwrap_or(fallback_duration);
+            durations.push((spec, duration));
+        }
+        durations.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        let mut loads: Vec<f64> = vec![0.0; instance_ids.len()];
+        let mut positions: Vec<i64> = vec![0; instance_ids.len()];
+        for (spec, duration) in durations {
+            let (least_loaded, _) = loads
+                .iter()
+                .enumerate()
+                .min_by(|a, b| a.1.total_cmp(b.1))
+                .expect("instance_ids is non-empty, checked above");
+
+            tx.execute(
+                sql::INSERT_PENDING_SPEC,
+                params![build_id, instance_ids[least_loaded], spec, positions[least_loaded]],
+            )?;
+            loads[least_loaded] += duration;
+            positions[least_loaded] += 1;
+        }
+
+        tx.execute(sql::DELETE_REMAINING_SPECS_FOR_BUILD, params![build_id])?;
+        tx.commit()?;
+        Ok(true)
+    }
+
+    /// Finds instances that haven't been seen since `threshold`, re-queues
+    /// whatever specs they pulled but never reported a result for onto a
+    /// surviving instance in the same build (falling back to the build's
+    /// unassigned pool if every other instance is also dead), marks each
+    /// swept instance `dead` so it's excluded from future sweeps and from
+    /// `all_instances_done`'s tally, and returns how many specs were
+    /// re-queued.
+    ///
+    /// Returns the distinct build ids that had an instance swept, so the
+    /// caller can re-check `all_instances_done` for them: marking the last
+    /// still-`ongoing` instance of a build `dead` can itself complete that
+    /// build, and nothing else would notice.
+    ///
+    /// `threshold` is derived from last-seen-on-any-authenticated-request,
+    /// not a dedicated liveness signal, so this can't distinguish "crashed
+    /// mid-spec" from "still running a spec longer than the heartbeat
+    /// timeout and hasn't made any other request in the meantime." A spec
+    /// re-queued in the latter case runs twice: the swept instance's
+    /// eventual `/report-result` still lands. See the `heartbeat_timeout_secs`
+    /// doc comment on `Settings` for the operational contract this assumes
+    /// (instances must heartbeat independently of spec completion).
+    pub async fn requeue_dead_instances(
+        &self,
+        threshold: DateTime<Utc>,
+    ) -> rusqlite::Result<(u64, Vec<String>)> {
+        let mut conn = self.conn.lock().await;
+        let tx = conn.transaction()?;
+        let threshold = threshold.to_rfc3339();
+
+        let dead: Vec<(String, String)> = {
+            let mut stmt = tx.prepare(sql::SELECT_DEAD_INSTANCES)?;
+            stmt.query_map(params![threshold], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<rusqlite::Result<Vec<(String, String)>>>()?
+        };
+
+        let mut requeued = 0u64;
+        let mut affected_builds = Vec::new();
+        for (build_id, dead_instance_id) in dead {
+            let lost_specs: Vec<String> = {
+                let mut stmt = tx.prepare(sql::SELECT_UNREPORTED_SPECS_FOR_INSTANCE)?;
+                stmt.query_map(params![build_id, dead_instance_id], |row| row.get(0))?
+                    .collect::<rusqlite::Result<Vec<String>>>()?
+            };
+
+            for spec in lost_specs {
+                tx.execute(
+                    sql::DELETE_INSTANCE_SPEC,
+                    params![build_id, dead_instance_id, spec],
+                )?;
+
+                let alive: Option<String> = tx
+                    .query_row(
+                        sql::SELECT_ALIVE_INSTANCE,
+                        params![build_id, threshold, dead_instance_id],
+                        |row| row.get(0),
+                    )
+                    .ok();
+
+                match alive {
+                    Some(alive_instance_id) => {
+                        let position: i64 = tx.query_row(
+                            sql::COUNT_PENDING_SPECS_FOR_INSTANCE,
+                            params![build_id, alive_instance_id],
+                            |row| row.get(0),
+                        )?;
+                        tx.execute(
+                            sql::INSERT_PENDING_SPEC,
+                            params![build_id, alive_instance_id, spec, position],
+                        )?;
+                    }
+                    None => {
+                        let position: i64 = tx.query_row(
+                            sql::COUNT_REMAINING_SPECS_FOR_BUILD,
+                            params![build_id],
+                            |row| row.get(0),
+                        )?;
+                        tx.execute(
+                            sql::INSERT_REMAINING_SPEC,
+                            params![build_id, spec, position],
+                        )?;
+                    }
+                }
+                requeued += 1;
+            }
+
+            tx.execute(
+                sql::SET_INSTANCE_STATUS,
+                params![build_id, dead_instance_id, "dead"],
+            )?;
+            affected_builds.push(build_id);
+        }
+
+        tx.commit()?;
+        affected_builds.sort_unstable();
+        affected_builds.dedup();
+        Ok((requeued, affected_builds))
+    }
+
+    /// `true` once every instance registered for `build_id` has reported
+    /// completion. Used to decide when the notifier should stop posting
+    /// "pending" and report a final outcome instead. Instances swept up as
+    /// `dead` by `requeue_dead_instances` are excluded from the tally, or a
+    /// build that lost a runner could never reach this state.
+    pub async fn all_instances_done(&self, build_id: &str) -> rusqlite::Result<bool> {
+        let conn = self.conn.lock().await;
+        let total: i64 = conn.query_row(
+            sql::COUNT_ACTIVE_INSTANCES_FOR_BUILD,
+            params![build_id],
+            |row| row.get(0),
+        )?;
+        if total == 0 {
+            return Ok(false);
+        }
+        let done: i64 = conn.query_row(
+            sql::COUNT_DONE_INSTANCES_FOR_BUILD,
+            params![build_id],
+            |row| row.get(0),
+        )?;
+        Ok(done == total)
+    }
+
+    pub async fn mark_instance_done(
+        &self,
+        build_id: &str,
+        instance_id: &str,
+    ) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().await;
+        conn.execute(
+            sql::SET_INSTANCE_STATUS,
+            params![build_id, instance_id, status_str(&TestStatus::Done)],
+        )?;
+        Ok(())
+    }
+
+    /// Records a single spec's outcome, optionally stashing its log under
+    /// `log_ref` so `/build-log/{spec}` can stream it back later.
+    pub async fn record_test_result(
+        &self,
+        build_id: &str,
+        spec: &str,
+        passed: bool,
+        duration_ms: u64,
+        log_ref: Option<&str>,
+        log: Option<&str>,
+    ) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().await;
+        conn.execute(
+            sql::UPSERT_TEST_RESULT,
+            params![build_id, spec, passed, duration_ms as i64, log_ref],
+        )?;
+        if let (Some(log_ref), Some(log)) = (log_ref, log) {
+            conn.execute(sql::UPSERT_LOG, params![build_id, log_ref, log])?;
+        }
+        Ok(())
+    }
+
+    pub async fn load_log(
+        &self,
+        build_id: &str,
+        log_ref: &str,
+    ) -> rusqlite::Result<Option<String>> {
+        let conn = self.conn.lock().await;
+        match conn.query_row(sql::SELECT_LOG, params![build_id, log_ref], |row| row.get(0)) {
+            Ok(content) => Ok(Some(content)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Whether any reported result for the build has failed. Drives the
+    /// notifier's success/failure status once every instance is done.
+    pub async fn build_has_failures(&self, build_id: &str) -> rusqlite::Result<bool> {
+        let conn = self.conn.lock().await;
+        let failed: i64 = conn.query_row(
+            sql::COUNT_FAILED_RESULTS_FOR_BUILD,
+            params![build_id],
+            |row| row.get(0),
+        )?;
+        Ok(failed > 0)
+    }
+
+    /// Wipes every row for `build_id`, including its `test_results` and
+    /// `logs`. `build_id`s are reused across re-runs of the same commit
+    /// (`ensure_build`'s `INSERT OR IGNORE` is keyed on them), so leaving
+    /// either table behind would let a prior run's stale results leak into
+    /// the next one: `build_has_failures` would see the old failing rows,
+    /// and `SELECT_UNREPORTED_SPECS_FOR_INSTANCE` would treat old specs as
+    /// already reported and skip re-queuing them.
+    pub async fn remove_build(&self, build_id: &str) -> rusqlite::Result<()> {
+        let mut conn = self.conn.lock().await;
+        let tx = conn.transaction()?;
+        tx.execute(sql::DELETE_INSTANCE_SPECS_FOR_BUILD, params![build_id])?;
+        tx.execute(sql::DELETE_REMAINING_SPECS_FOR_BUILD, params![build_id])?;
+        tx.execute(sql::DELETE_PENDING_SPECS_FOR_BUILD, params![build_id])?;
+        tx.execute(sql::DELETE_TEST_RESULTS_FOR_BUILD, params![build_id])?;
+        tx.execute(sql::DELETE_LOGS_FOR_BUILD, params![build_id])?;
+        tx.execute(sql::DELETE_INSTANCES_FOR_BUILD, params![build_id])?;
+        tx.execute(sql::DELETE_BUILD, params![build_id])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// The `DELETE ... WHERE created_on < ?` sweep that replaces the old
+    /// in-memory `clear_old_data` scan. Deliberately leaves `test_results`
+    /// in place: `partition_specs_if_ready` looks up `SELECT_AVG_DURATION_FOR_SPEC`
+    /// by spec name alone (not scoped to a build), so history from expired
+    /// builds still feeds LPT's duration estimates for future ones. `logs`
+    /// holds full per-spec log blobs with no such cross-build use, so those
+    /// are reclaimed here instead of growing unbounded.
+    pub async fn clear_builds_older_than(
+        &self,
+        threshold: DateTime<Utc>,
+    ) -> rusqlite::Result<()> {
+        let mut conn = self.conn.lock().await;
+        let tx = conn.transaction()?;
+
+        let expired: Vec<String> = {
+            let mut stmt = tx.prepare(sql::SELECT_BUILDS_CREATED_BEFORE)?;
+            stmt.query_map(params![threshold.to_rfc3339()], |row| row.get(0))?
+                .collect::<rusqlite::Result<Vec<String>>>()?
+        };
+
+        for build_id in expired {
+            tx.execute(sql::DELETE_INSTANCE_SPECS_FOR_BUILD, params![build_id])?;
+            tx.execute(sql::DELETE_REMAINING_SPECS_FOR_BUILD, params![build_id])?;
+            tx.execute(sql::DELETE_PENDING_SPECS_FOR_BUILD, params![build_id])?;
+            tx.execute(sql::DELETE_LOGS_FOR_BUILD, params![build_id])?;
+            tx.execute(sql::DELETE_INSTANCES_FOR_BUILD, params![build_id])?;
+            tx.execute(sql::DELETE_BUILD, params![build_id])?;
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+fn status_str(status: &TestStatus) -> &'static str {
+    match status {
+        TestStatus::Ongoing => "ongoing",
+        TestStatus::Done => "done",
+    }
+}
+
+fn parse_status(status: &str) -> TestStatus {
+    match status {
+        "done" => TestStatus::Done,
+        _ => TestStatus::Ongoing,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn partition_specs_if_ready_waits_for_expected_instance_count() {
+        let db = DbCtx::open(":memory:").unwrap();
+        db.ensure_build("b1", &["a".into(), "b".into(), "c".into(), "d".into()])
+            .await
+            .unwrap();
+        db.upsert_instance("b1", "i1", true).await.unwrap();
+
+        // Only one of the two expected instances has registered: no-op.
+        assert!(!db
+            .partition_specs_if_ready("b1", 2, 1000)
+            .await
+            .unwrap());
+        let pending_for_i1: i64 = db
+            .conn
+            .lock()
+            .await
+            .query_row(
+                sql::COUNT_PENDING_SPECS_FOR_INSTANCE,
+                params!["b1", "i1"],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(pending_for_i1, 0);
+
+        db.upsert_instance("b1", "i2", false).await.unwrap();
+        assert!(db
+            .partition_specs_if_ready("b1", 2, 1000)
+            .await
+            .unwrap());
+
+        // No recorded history yet, so every spec gets the same estimated
+        // duration and LPT's greedy least-loaded pick splits them evenly.
+        let mut i1_specs = Vec::new();
+        while let Some(spec) = db.take_next_spec("b1", "i1").await.unwrap() {
+            i1_specs.push(spec);
+        }
+        let mut i2_specs = Vec::new();
+        while let Some(spec) = db.take_next_spec("b1", "i2").await.unwrap() {
+            i2_specs.push(spec);
+        }
+        assert_eq!(i1_specs.len(), 2);
+        assert_eq!(i2_specs.len(), 2);
+
+        // Already partitioned, and the pool is now empty: a second call
+        // stays a no-op rather than re-partitioning assigned work.
+        assert!(!db
+            .partition_specs_if_ready("b1", 2, 1000)
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn take_next_spec_falls_back_to_remaining_pool_before_partitioning() {
+        let db = DbCtx::open(":memory:").unwrap();
+        db.ensure_build("b1", &["only-spec".into()]).await.unwrap();
+        db.upsert_instance("b1", "i1", true).await.unwrap();
+
+        // expected_instance_count of 2 is never reached, so the spec stays
+        // in remaining_specs. An instance asking for work anyway (the
+        // staggered-registration case) must still get it.
+        let spec = db.take_next_spec("b1", "i1").await.unwrap();
+        assert_eq!(spec.as_deref(), Some("only-spec"));
+    }
+
+    #[tokio::test]
+    async fn requeue_dead_instance_reassigns_to_surviving_peer() {
+        let db = DbCtx::open(":memory:").unwrap();
+        db.ensure_build("b1", &["spec-a".into()]).await.unwrap();
+        db.upsert_instance("b1", "i1", true).await.unwrap();
+        db.upsert_instance("b1", "i2", false).await.unwrap();
+        db.partition_specs_if_ready("b1", 2, 1000).await.unwrap();
+
+        // Whichever instance the single spec landed on (LPT's tie-break
+        // isn't part of this contract) is the one we'll declare dead.
+        let (holder, peer) = match db.take_next_spec("b1", "i1").await.unwrap() {
+            Some(spec) => {
+                assert_eq!(spec, "spec-a");
+                ("i1", "i2")
+            }
+            None => {
+                let spec = db.take_next_spec("b1", "i2").await.unwrap();
+                assert_eq!(spec.as_deref(), Some("spec-a"));
+                ("i2", "i1")
+            }
+        };
+
+        db.touch_instance("b1", holder).await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        let threshold = Utc::now();
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        db.touch_instance("b1", peer).await.unwrap();
+
+        let (requeued, affected) = db.requeue_dead_instances(threshold).await.unwrap();
+        assert_eq!(requeued, 1);
+        assert_eq!(affected, vec!["b1".to_string()]);
+
+        // Re-queued onto the surviving peer, not the unassigned pool.
+        let reassigned = db.take_next_spec("b1", peer).await.unwrap();
+        assert_eq!(reassigned.as_deref(), Some("spec-a"));
+
+        // The dead instance is excluded from all_instances_done's tally, or
+        // this build could never be reported complete.
+        assert!(!db.all_instances_done("b1").await.unwrap());
+        db.mark_instance_done("b1", peer).await.unwrap();
+        assert!(db.all_instances_done("b1").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn requeue_dead_instance_falls_back_to_remaining_pool_when_no_peer_survives() {
+        let db = DbCtx::open(":memory:").unwrap();
+        db.ensure_build("b2", &["spec-a".into()]).await.unwrap();
+        db.upsert_instance("b2", "i1", true).await.unwrap();
+        db.partition_specs_if_ready("b2", 1, 1000).await.unwrap();
+        db.take_next_spec("b2", "i1").await.unwrap();
+
+        // A threshold in the future treats every instance as dead.
+        let future_threshold = Utc::now() + chrono::Duration::hours(1);
+        let (requeued, affected) = db.requeue_dead_instances(future_threshold).await.unwrap();
+        assert_eq!(requeued, 1);
+        assert_eq!(affected, vec!["b2".to_string()]);
+
+        // No surviving instance to hand it to: it lands back in the
+        // unassigned pool, where a never-before-seen instance can still
+        // pick it up via take_next_spec's fallback.
+        let recovered = db.take_next_spec("b2", "i2").await.unwrap();
+        assert_eq!(recovered.as_deref(), Some("spec-a"));
+    }
+}
@@ -1,20 +1,28 @@
+mod dbctx;
+mod notifier;
+mod sql;
+mod webhook;
+
 use std::collections::{HashMap, VecDeque};
 use std::future::{ready, Ready};
 
-use actix_web::web::{Data, Json};
+use actix_web::web::{Bytes, Data, Json, Path};
 use actix_web::{
-    error, get, App, FromRequest, HttpRequest, HttpResponse, HttpServer, Responder, Result,
+    error, get, post, App, FromRequest, HttpRequest, HttpResponse, HttpServer, Responder, Result,
 };
 use chrono::Utc;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use tokio::sync::mpsc::UnboundedSender;
 use tokio::sync::Mutex;
 
+use dbctx::DbCtx;
+use notifier::{CommitState, Update as NotifierUpdate};
 
 // === "Domain" types ===
 
-#[derive(Serialize, PartialEq)]
-enum TestStatus {
+#[derive(Serialize, PartialEq, Clone)]
+pub(crate) enum TestStatus {
     #[serde(rename = "ongoing")]
     Ongoing,
     #[serde(rename = "done")]
@@ -22,20 +30,36 @@ enum TestStatus {
 }
 
 struct AppState {
+    db: DbCtx,
+    // In-memory copy of each build's instances, written alongside the
+    // database by the handlers. Nothing reads from it: every request still
+    // goes to the database, which is always the source of truth.
     build_map: Mutex<HashMap<String, Build>>,
+    notifier: UnboundedSender<NotifierUpdate>,
 }
 
-struct Build {
+pub(crate) struct Build {
     instance_map: HashMap<String, Instance>,
     created_on: chrono::DateTime<Utc>,
     test_spec_list: VecDeque<String>,
 }
 
-#[derive(Serialize)]
-struct Instance {
-    test_list: Vec<String>,
-    test_status: TestStatus,
-    is_master: bool,
+#[derive(Serialize, Clone)]
+pub(crate) struct Instance {
+    pub(crate) test_list: Vec<String>,
+    pub(crate) test_status: TestStatus,
+    pub(crate) is_master: bool,
+}
+
+/// The outcome of a single spec, as reported by an instance via
+/// `/report-result`. `log_ref` is the key an instance (or a dashboard) can
+/// later hand to `/build-log/{spec}` to stream the associated output.
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct TestResult {
+    pub(crate) spec: String,
+    pub(crate) passed: bool,
+    pub(crate) duration_ms: u64,
+    pub(crate) log_ref: Option<String>,
 }
 
 // === Extractors ===
@@ -87,50 +111,138 @@ struct RegisterInstanceData {
 #[get("/register-instance")]
 async fn register_instance(
     state: Data<AppState>,
+    config: Data<Settings>,
     specs: Json<RegisterInstanceData>,
     meta: RequestMeta,
 ) -> Result<impl Responder> {
+    clear_old_data(&state.db).await;
+
     let mut build_map = state.build_map.lock().await;
-    clear_old_data(&mut build_map).await;
 
-    let build = build_map.entry(meta.build_id).or_insert(Build {
-        created_on: Utc::now(),
+    let specs: Vec<String> = specs.test_spec_list.iter().cloned().collect();
+    let created_on = state
+        .db
+        .ensure_build(&meta.build_id, &specs)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
+    let is_master = state
+        .db
+        .instance_count(&meta.build_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+        == 0;
+
+    state
+        .db
+        .upsert_instance(&meta.build_id, &meta.instance_id, is_master)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
+    // Once enough instances are in, carve up the remaining specs by
+    // estimated duration (LPT) instead of leaving them in raw FIFO order.
+    state
+        .db
+        .partition_specs_if_ready(
+            &meta.build_id,
+            config.expected_instance_count,
+            config.default_spec_duration_ms,
+        )
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
+    let instance = state
+        .db
+        .load_instance(&meta.build_id, &meta.instance_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+        .ok_or_else(|| error::ErrorInternalServerError("Instance vanished after insert"))?;
+
+    let build = build_map.entry(meta.build_id.clone()).or_insert(Build {
+        created_on,
         instance_map: HashMap::default(),
-        test_spec_list: specs.test_spec_list.clone(),
+        test_spec_list: VecDeque::new(),
     });
+    build
+        .instance_map
+        .insert(meta.instance_id.clone(), instance.clone());
 
-    build.instance_map.insert(
-        meta.instance_id.clone(),
-        Instance {
-            test_list: vec![],
-            test_status: TestStatus::Ongoing,
-            // First one becomes "master"
-            is_master: build.instance_map.is_empty(),
-        },
-    );
+    let _ = state.notifier.send(NotifierUpdate {
+        build_id: meta.build_id,
+        state: CommitState::Pending,
+        description: "Tests are running",
+    });
 
-    return Ok(HttpResponse::Ok().json(build.instance_map.get(&meta.instance_id).unwrap()));
+    Ok(HttpResponse::Ok().json(instance))
+}
+
+/// Pre-creates a build from a GitHub push event so instances that register
+/// afterwards join a build that's already known, rather than inventing one
+/// on the fly. Verifies `X-Hub-Signature-256` against the raw body before
+/// touching the JSON, per GitHub's webhook security guidance.
+#[post("/webhook/github")]
+async fn github_webhook(
+    req: HttpRequest,
+    body: Bytes,
+    state: Data<AppState>,
+    config: Data<Settings>,
+) -> Result<impl Responder> {
+    let signature = req
+        .headers()
+        .get("X-Hub-Signature-256")
+        .and_then(|value| value.to_str().ok())
+        .ok_or_else(|| error::ErrorBadRequest("Missing X-Hub-Signature-256 header"))?;
+
+    if !webhook::verify_signature(&config.webhook_secrets, &body, signature) {
+        return Err(error::ErrorBadRequest("Signature does not match"));
+    }
+
+    let event: webhook::PushEvent =
+        serde_json::from_slice(&body).map_err(error::ErrorBadRequest)?;
+    let build_id = event
+        .build_id()
+        .ok_or_else(|| error::ErrorBadRequest("Push event has no head_commit"))?;
+
+    state
+        .db
+        .ensure_build(&build_id, &config.default_test_spec_list)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(json!({ "build_id": build_id })))
+}
+
+#[get("/heartbeat")]
+async fn heartbeat(meta: RequestMeta, state: Data<AppState>) -> Result<impl Responder> {
+    state
+        .db
+        .touch_instance(&meta.build_id, &meta.instance_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(json!({})))
 }
 
 #[get("/get-next-test-spec")]
 async fn next_spec(meta: RequestMeta, state: Data<AppState>) -> Result<impl Responder> {
-    let mut build_map = state.build_map.lock().await;
+    state
+        .db
+        .touch_instance(&meta.build_id, &meta.instance_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
+    let next_test = state
+        .db
+        .take_next_spec(&meta.build_id, &meta.instance_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+        .unwrap_or_default();
 
-    let next_test = build_map
-        .get_mut(&meta.build_id)
-        .ok_or_else(|| error::ErrorBadRequest("Build not found"))?
-        .test_spec_list
-        .pop_front()
-        .unwrap_or_else(|| "".to_string());
-
-    build_map
-        .get_mut(&meta.build_id)
-        .ok_or_else(|| error::ErrorBadRequest("Build not found"))?
-        .instance_map
-        .get_mut(&meta.instance_id)
-        .ok_or_else(|| error::ErrorBadRequest("Instance not found"))?
-        .test_list
-        .push(next_test.clone());
+    let mut build_map = state.build_map.lock().await;
+    if let Some(build) = build_map.get_mut(&meta.build_id) {
+        if let Some(instance) = build.instance_map.get_mut(&meta.instance_id) {
+            instance.test_list.push(next_test.clone());
+        }
+    }
 
     Ok(HttpResponse::Ok().json(json!({
         "status": if next_test.is_empty() { TestStatus::Done } else { TestStatus::Ongoing },
@@ -140,37 +252,183 @@ async fn next_spec(meta: RequestMeta, state: Data<AppState>) -> Result<impl Resp
 
 #[get("/test-completed")]
 async fn test_completed(meta: RequestMeta, state: Data<AppState>) -> Result<impl Responder> {
+    state
+        .db
+        .touch_instance(&meta.build_id, &meta.instance_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
+    state
+        .db
+        .mark_instance_done(&meta.build_id, &meta.instance_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
     let mut build_map = state.build_map.lock().await;
+    if let Some(build) = build_map.get_mut(&meta.build_id) {
+        if let Some(instance) = build.instance_map.get_mut(&meta.instance_id) {
+            instance.test_status = TestStatus::Done;
+        }
+    }
+    drop(build_map);
 
-    build_map
-        .get_mut(&meta.build_id)
-        .ok_or_else(|| error::ErrorBadRequest("Build not found"))?
-        .instance_map
-        .get_mut(&meta.instance_id)
-        .ok_or_else(|| error::ErrorBadRequest("Instance not found"))?
-        .test_status = TestStatus::Done;
+    notify_if_build_done(&state, meta.build_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(json!({})))
+}
+
+/// Posts the build's final commit status once every instance is done,
+/// whether that happened because the last one called `/test-completed` or
+/// because the dead-instance sweep marked it `dead` instead. A no-op if the
+/// build still has an instance that's neither, or if every instance is
+/// `dead`.
+async fn notify_if_build_done(state: &AppState, build_id: String) -> rusqlite::Result<()> {
+    if !state.db.all_instances_done(&build_id).await? {
+        return Ok(());
+    }
+
+    let has_failures = state.db.build_has_failures(&build_id).await?;
+    let (commit_state, description) = if has_failures {
+        (CommitState::Failure, "One or more tests failed")
+    } else {
+        (CommitState::Success, "All tests passed")
+    };
+
+    let _ = state.notifier.send(NotifierUpdate {
+        build_id,
+        state: commit_state,
+        description,
+    });
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct ReportResultData {
+    spec: String,
+    passed: bool,
+    duration_ms: u64,
+    log: Option<String>,
+}
+
+#[post("/report-result")]
+async fn report_result(
+    meta: RequestMeta,
+    state: Data<AppState>,
+    body: Json<ReportResultData>,
+) -> Result<impl Responder> {
+    state
+        .db
+        .touch_instance(&meta.build_id, &meta.instance_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
+    // The spec name doubles as the log's ref: result and log are reported
+    // together, so there's no separate id to hand out or track.
+    let log_ref = body.log.is_some().then(|| body.spec.clone());
+
+    state
+        .db
+        .record_test_result(
+            &meta.build_id,
+            &body.spec,
+            body.passed,
+            body.duration_ms,
+            log_ref.as_deref(),
+            body.log.as_deref(),
+        )
+        .await
+        .map_err(error::ErrorInternalServerError)?;
 
     Ok(HttpResponse::Ok().json(json!({})))
 }
 
+/// Sends a previously reported log back as a chunked body of 8 KiB pieces,
+/// the way build-o-tron streams stored artifacts. The whole log is loaded
+/// from the database into memory before the first chunk is sent.
+#[get("/build-log/{spec}")]
+async fn build_log(
+    meta: RequestMeta,
+    state: Data<AppState>,
+    spec: Path<String>,
+) -> Result<impl Responder> {
+    state
+        .db
+        .touch_instance(&meta.build_id, &meta.instance_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
+    let content = state
+        .db
+        .load_log(&meta.build_id, &spec)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+        .ok_or_else(|| error::ErrorNotFound("No log stored for that spec"))?;
+
+    const CHUNK_SIZE: usize = 8 * 1024;
+    let chunks: Vec<Result<Bytes, actix_web::Error>> = content
+        .into_bytes()
+        .chunks(CHUNK_SIZE)
+        .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+        .collect();
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain; charset=utf-8")
+        .streaming(futures::stream::iter(chunks)))
+}
+
 #[get("/reset")]
 async fn reset_data(meta: RequestMeta, state: Data<AppState>) -> Result<impl Responder> {
+    state
+        .db
+        .remove_build(&meta.build_id)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
     let mut build_map = state.build_map.lock().await;
     build_map.remove(&meta.build_id);
     Ok(HttpResponse::Ok())
 }
 
-async fn clear_old_data(build_map: &mut HashMap<String, Build>) {
+async fn clear_old_data(db: &DbCtx) {
     let threshold = Utc::now() - chrono::Duration::hours(2);
+    if let Err(err) = db.clear_builds_older_than(threshold).await {
+        eprintln!("failed to sweep expired builds: {err}");
+    }
+}
 
-    let expired_builds: Vec<String> = build_map
-        .iter()
-        .filter(|(_, build)| build.created_on < threshold)
-        .map(|(id, _)| id.clone())
-        .collect();
-
-    expired_builds.iter().for_each(|id| {
-        let _ = &build_map.remove(id);
+/// Background counterpart to `clear_old_data`: on a fixed tokio interval,
+/// re-queues specs belonging to instances that have gone quiet for longer
+/// than `timeout`, so a crashed or pre-empted CI runner doesn't strand
+/// work forever. Also re-checks completion for any build the sweep touched —
+/// marking the last `ongoing` instance of a build `dead` can itself finish
+/// that build, and unlike `/test-completed` there's no request in flight to
+/// notice and notify.
+fn spawn_dead_instance_sweeper(
+    state: Data<AppState>,
+    timeout: chrono::Duration,
+    interval: std::time::Duration,
+) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            let threshold = Utc::now() - timeout;
+            match state.db.requeue_dead_instances(threshold).await {
+                Ok((n, affected_builds)) => {
+                    if n > 0 {
+                        eprintln!("re-queued {n} spec(s) from dead instances");
+                    }
+                    for build_id in affected_builds {
+                        if let Err(err) = notify_if_build_done(&state, build_id).await {
+                            eprintln!("failed to check build completion after sweep: {err}");
+                        }
+                    }
+                }
+                Err(err) => eprintln!("failed to sweep dead instances: {err}"),
+            }
+        }
     });
 }
 
@@ -180,6 +438,26 @@ async fn clear_old_data(build_map: &mut HashMap<String, Build>) {
 struct Settings {
     port: u16,
     token: String,
+    db_path: String,
+    webhook_secrets: Vec<String>,
+    default_test_spec_list: Vec<String>,
+    notifier: notifier::NotifierConfig,
+    // How many instances `partition_specs_if_ready` waits for before
+    // carving up a build's remaining specs via LPT.
+    expected_instance_count: i64,
+    default_spec_duration_ms: u64,
+    // Dead-instance sweep: an instance is considered gone once it's been
+    // this many seconds since its last authenticated request, and is
+    // checked for on this interval. `last_seen` advances on ANY
+    // authenticated request, not a dedicated liveness ping, so an instance
+    // running a single long spec must call /heartbeat on its own timer
+    // independent of spec completion, or it'll be declared dead and its
+    // in-flight spec re-queued to someone else — `report-result` for it
+    // still lands from the original instance, so the spec runs twice. Set
+    // this comfortably above the runner's heartbeat interval, not above the
+    // longest expected spec duration.
+    heartbeat_timeout_secs: u64,
+    heartbeat_sweep_interval_secs: u64,
 }
 
 fn get_configuration() -> Settings {
@@ -195,20 +473,37 @@ fn get_configuration() -> Settings {
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
+    let config = get_configuration();
+    let port = config.port;
+
+    let db = DbCtx::open(&config.db_path)
+        .unwrap_or_else(|err| panic!("failed to open database at {}: {err}", config.db_path));
+
+    let notifier = notifier::spawn(config.notifier.clone());
+
     let state = Data::new(AppState {
+        db,
         build_map: Mutex::new(HashMap::new()),
+        notifier,
     });
 
-    let config = get_configuration();
-    let port = config.port;
+    spawn_dead_instance_sweeper(
+        state.clone(),
+        chrono::Duration::seconds(config.heartbeat_timeout_secs as i64),
+        std::time::Duration::from_secs(config.heartbeat_sweep_interval_secs),
+    );
 
     HttpServer::new(move || {
         App::new()
             .service(health_check)
+            .service(github_webhook)
             .service(register_instance)
             .service(next_spec)
             .service(test_completed)
+            .service(report_result)
+            .service(build_log)
             .service(reset_data)
+            .service(heartbeat)
             .app_data(state.clone())
             .app_data(Data::new(config.clone()))
     })
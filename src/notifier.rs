@@ -0,0 +1,78 @@
+//! Posts build progress back to the VCS as commit statuses, the way
+//! build-o-tron's notifier does. Decoupled from request handling via an
+//! unbounded channel: handlers fire-and-forget an `Update` and a single
+//! background task serializes the outbound HTTP calls, so a slow or
+//! unreachable VCS API never adds latency to `/register-instance` or
+//! `/test-completed`.
+
+use serde::Deserialize;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+#[derive(Deserialize, Clone)]
+pub struct NotifierConfig {
+    pub api_base: String,
+    pub token: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommitState {
+    Pending,
+    Success,
+    Failure,
+}
+
+impl CommitState {
+    fn as_str(&self) -> &'static str {
+        match self {
+            CommitState::Pending => "pending",
+            CommitState::Success => "success",
+            CommitState::Failure => "failure",
+        }
+    }
+}
+
+pub struct Update {
+    pub build_id: String,
+    pub state: CommitState,
+    pub description: &'static str,
+}
+
+/// Spawns the background notifier task and returns the handle handlers use
+/// to queue updates. Dropping every clone of the returned sender ends the
+/// task.
+pub fn spawn(config: NotifierConfig) -> UnboundedSender<Update> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    tokio::spawn(run(config, rx));
+    tx
+}
+
+async fn run(config: NotifierConfig, mut rx: UnboundedReceiver<Update>) {
+    let client = reqwest::Client::new();
+    while let Some(update) = rx.recv().await {
+        if let Err(err) = post_status(&client, &config, &update).await {
+            eprintln!(
+                "failed to notify VCS of {} for build {}: {err}",
+                update.state.as_str(),
+                update.build_id
+            );
+        }
+    }
+}
+
+async fn post_status(
+    client: &reqwest::Client,
+    config: &NotifierConfig,
+    update: &Update,
+) -> reqwest::Result<()> {
+    client
+        .post(format!("{}/commit-status/{}", config.api_base, update.build_id))
+        .bearer_auth(&config.token)
+        .json(&serde_json::json!({
+            "state": update.state.as_str(),
+            "description": update.description,
+        }))
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
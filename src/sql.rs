@@ -0,0 +1,174 @@
+//! Raw SQL used by `DbCtx`. Kept separate from the connection/transaction
+//! plumbing so the schema and queries can be read (and reviewed) without
+//! wading through Rust control flow.
+
+pub const CREATE_TABLES: &str = "
+CREATE TABLE IF NOT EXISTS builds (
+    id         TEXT PRIMARY KEY,
+    created_on TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS instances (
+    build_id    TEXT NOT NULL,
+    instance_id TEXT NOT NULL,
+    is_master   INTEGER NOT NULL,
+    status      TEXT NOT NULL,
+    last_seen   TEXT NOT NULL,
+    PRIMARY KEY (build_id, instance_id)
+);
+
+CREATE TABLE IF NOT EXISTS instance_specs (
+    build_id    TEXT NOT NULL,
+    instance_id TEXT NOT NULL,
+    spec        TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS remaining_specs (
+    build_id TEXT NOT NULL,
+    spec     TEXT NOT NULL,
+    position INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS pending_specs (
+    build_id    TEXT NOT NULL,
+    instance_id TEXT NOT NULL,
+    spec        TEXT NOT NULL,
+    position    INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS test_results (
+    build_id    TEXT NOT NULL,
+    spec        TEXT NOT NULL,
+    passed      INTEGER NOT NULL,
+    duration_ms INTEGER NOT NULL,
+    log_ref     TEXT,
+    PRIMARY KEY (build_id, spec)
+);
+
+CREATE TABLE IF NOT EXISTS logs (
+    build_id TEXT NOT NULL,
+    log_ref  TEXT NOT NULL,
+    content  TEXT NOT NULL,
+    PRIMARY KEY (build_id, log_ref)
+);
+";
+
+pub const INSERT_BUILD: &str =
+    "INSERT OR IGNORE INTO builds (id, created_on) VALUES (?1, ?2)";
+
+pub const SELECT_BUILD: &str = "SELECT id, created_on FROM builds WHERE id = ?1";
+
+pub const DELETE_BUILD: &str = "DELETE FROM builds WHERE id = ?1";
+pub const DELETE_INSTANCES_FOR_BUILD: &str = "DELETE FROM instances WHERE build_id = ?1";
+pub const DELETE_INSTANCE_SPECS_FOR_BUILD: &str =
+    "DELETE FROM instance_specs WHERE build_id = ?1";
+pub const DELETE_REMAINING_SPECS_FOR_BUILD: &str =
+    "DELETE FROM remaining_specs WHERE build_id = ?1";
+
+pub const DELETE_TEST_RESULTS_FOR_BUILD: &str =
+    "DELETE FROM test_results WHERE build_id = ?1";
+
+pub const DELETE_LOGS_FOR_BUILD: &str = "DELETE FROM logs WHERE build_id = ?1";
+
+pub const SELECT_BUILDS_CREATED_BEFORE: &str = "SELECT id FROM builds WHERE created_on < ?1";
+
+pub const UPSERT_INSTANCE: &str = "
+INSERT INTO instances (build_id, instance_id, is_master, status, last_seen)
+VALUES (?1, ?2, ?3, ?4, ?5)
+ON CONFLICT (build_id, instance_id) DO UPDATE SET
+    status = excluded.status,
+    last_seen = excluded.last_seen";
+
+pub const SELECT_INSTANCE: &str =
+    "SELECT is_master, status FROM instances WHERE build_id = ?1 AND instance_id = ?2";
+
+pub const TOUCH_INSTANCE: &str =
+    "UPDATE instances SET last_seen = ?3 WHERE build_id = ?1 AND instance_id = ?2";
+
+pub const SELECT_DEAD_INSTANCES: &str =
+    "SELECT build_id, instance_id FROM instances WHERE status = 'ongoing' AND last_seen < ?1";
+
+pub const SELECT_ALIVE_INSTANCE: &str = "
+SELECT instance_id FROM instances
+WHERE build_id = ?1 AND status = 'ongoing' AND last_seen >= ?2 AND instance_id != ?3
+LIMIT 1";
+
+pub const SELECT_UNREPORTED_SPECS_FOR_INSTANCE: &str = "
+SELECT spec FROM instance_specs
+WHERE build_id = ?1 AND instance_id = ?2
+AND spec NOT IN (SELECT spec FROM test_results WHERE test_results.build_id = ?1)";
+
+pub const DELETE_INSTANCE_SPEC: &str =
+    "DELETE FROM instance_specs WHERE build_id = ?1 AND instance_id = ?2 AND spec = ?3";
+
+pub const COUNT_PENDING_SPECS_FOR_INSTANCE: &str =
+    "SELECT COUNT(*) FROM pending_specs WHERE build_id = ?1 AND instance_id = ?2";
+
+pub const COUNT_INSTANCES_FOR_BUILD: &str =
+    "SELECT COUNT(*) FROM instances WHERE build_id = ?1";
+
+pub const COUNT_DONE_INSTANCES_FOR_BUILD: &str =
+    "SELECT COUNT(*) FROM instances WHERE build_id = ?1 AND status = 'done'";
+
+pub const COUNT_ACTIVE_INSTANCES_FOR_BUILD: &str =
+    "SELECT COUNT(*) FROM instances WHERE build_id = ?1 AND status != 'dead'";
+
+pub const SET_INSTANCE_STATUS: &str =
+    "UPDATE instances SET status = ?3 WHERE build_id = ?1 AND instance_id = ?2";
+
+pub const INSERT_REMAINING_SPEC: &str =
+    "INSERT INTO remaining_specs (build_id, spec, position) VALUES (?1, ?2, ?3)";
+
+pub const SELECT_NEXT_REMAINING_SPEC: &str =
+    "SELECT rowid, spec FROM remaining_specs WHERE build_id = ?1 ORDER BY position ASC LIMIT 1";
+
+pub const DELETE_REMAINING_SPEC_BY_ROWID: &str = "DELETE FROM remaining_specs WHERE rowid = ?1";
+
+pub const SELECT_REMAINING_SPECS_FOR_BUILD: &str =
+    "SELECT spec FROM remaining_specs WHERE build_id = ?1 ORDER BY position ASC";
+
+pub const COUNT_REMAINING_SPECS_FOR_BUILD: &str =
+    "SELECT COUNT(*) FROM remaining_specs WHERE build_id = ?1";
+
+pub const SELECT_INSTANCE_IDS_FOR_BUILD: &str =
+    "SELECT instance_id FROM instances WHERE build_id = ?1";
+
+pub const INSERT_PENDING_SPEC: &str =
+    "INSERT INTO pending_specs (build_id, instance_id, spec, position) VALUES (?1, ?2, ?3, ?4)";
+
+pub const SELECT_NEXT_PENDING_SPEC: &str = "
+SELECT rowid, spec FROM pending_specs
+WHERE build_id = ?1 AND instance_id = ?2
+ORDER BY position ASC LIMIT 1";
+
+pub const DELETE_PENDING_SPEC_BY_ROWID: &str = "DELETE FROM pending_specs WHERE rowid = ?1";
+
+pub const DELETE_PENDING_SPECS_FOR_BUILD: &str = "DELETE FROM pending_specs WHERE build_id = ?1";
+
+pub const SELECT_AVG_DURATION_FOR_SPEC: &str =
+    "SELECT AVG(duration_ms) FROM test_results WHERE spec = ?1";
+
+pub const SELECT_AVG_DURATION_OVERALL: &str = "SELECT AVG(duration_ms) FROM test_results";
+
+pub const INSERT_INSTANCE_SPEC: &str =
+    "INSERT INTO instance_specs (build_id, instance_id, spec) VALUES (?1, ?2, ?3)";
+
+pub const SELECT_INSTANCE_SPECS: &str =
+    "SELECT spec FROM instance_specs WHERE build_id = ?1 AND instance_id = ?2";
+
+pub const UPSERT_TEST_RESULT: &str = "
+INSERT INTO test_results (build_id, spec, passed, duration_ms, log_ref)
+VALUES (?1, ?2, ?3, ?4, ?5)
+ON CONFLICT (build_id, spec) DO UPDATE SET
+    passed = excluded.passed,
+    duration_ms = excluded.duration_ms,
+    log_ref = excluded.log_ref";
+
+pub const COUNT_FAILED_RESULTS_FOR_BUILD: &str =
+    "SELECT COUNT(*) FROM test_results WHERE build_id = ?1 AND passed = 0";
+
+pub const UPSERT_LOG: &str = "
+INSERT INTO logs (build_id, log_ref, content) VALUES (?1, ?2, ?3)
+ON CONFLICT (build_id, log_ref) DO UPDATE SET content = excluded.content";
+
+pub const SELECT_LOG: &str = "SELECT content FROM logs WHERE build_id = ?1 AND log_ref = ?2";
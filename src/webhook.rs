@@ -0,0 +1,106 @@
+//! GitHub push-webhook support: signature verification and the bits of the
+//! push payload we actually care about. Verification follows the same
+//! HMAC-SHA256-over-the-raw-body scheme build-o-tron's webserver uses for
+//! its GitHub webhook.
+
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Deserialize)]
+pub struct PushEvent {
+    pub head_commit: Option<HeadCommit>,
+    pub repository: Repository,
+}
+
+#[derive(Deserialize)]
+pub struct HeadCommit {
+    pub id: String,
+}
+
+#[derive(Deserialize)]
+pub struct Repository {
+    pub full_name: String,
+}
+
+impl PushEvent {
+    /// The build id a registering instance would need to know ahead of
+    /// time: the commit SHA, scoped to the repository it came from.
+    pub fn build_id(&self) -> Option<String> {
+        let sha = &self.head_commit.as_ref()?.id;
+        Some(format!("{}@{}", self.repository.full_name, sha))
+    }
+}
+
+/// Checks `raw_body` against the `X-Hub-Signature-256` header using each of
+/// `secrets` in turn (GitHub webhooks support rotating through multiple
+/// pre-shared keys). Returns `true` if any secret produces a match.
+pub fn verify_signature(secrets: &[String], raw_body: &[u8], signature_header: &str) -> bool {
+    let Some(hex_digest) = signature_header.strip_prefix("sha256=") else {
+        return false;
+    };
+    // `hex::encode` always produces lowercase; normalize the header to match
+    // so an uppercase-hex signature doesn't spuriously fail to verify.
+    let hex_digest = hex_digest.to_ascii_lowercase();
+
+    secrets.iter().any(|secret| {
+        let Ok(mut mac) = HmacSha256::new_from_slice(secret.as_bytes()) else {
+            return false;
+        };
+        mac.update(raw_body);
+        let expected = hex::encode(mac.finalize().into_bytes());
+        constant_time_eq(expected.as_bytes(), hex_digest.as_bytes())
+    })
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b.iter()).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sign(secret: &str, body: &[u8]) -> String {
+        let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(body);
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    #[test]
+    fn verifies_against_any_configured_secret() {
+        let secrets = vec!["old-secret".to_string(), "current-secret".to_string()];
+        let body = b"push payload";
+        let header = format!("sha256={}", sign("current-secret", body));
+        assert!(verify_signature(&secrets, body, &header));
+    }
+
+    #[test]
+    fn rejects_wrong_secret() {
+        let secrets = vec!["current-secret".to_string()];
+        let body = b"push payload";
+        let header = format!("sha256={}", sign("wrong-secret", body));
+        assert!(!verify_signature(&secrets, body, &header));
+    }
+
+    #[test]
+    fn rejects_missing_sha256_prefix() {
+        let secrets = vec!["current-secret".to_string()];
+        let body = b"push payload";
+        let digest = sign("current-secret", body);
+        assert!(!verify_signature(&secrets, body, &digest));
+    }
+
+    #[test]
+    fn accepts_uppercase_hex_digest() {
+        let secrets = vec!["current-secret".to_string()];
+        let body = b"push payload";
+        let header = format!("sha256={}", sign("current-secret", body).to_uppercase());
+        assert!(verify_signature(&secrets, body, &header));
+    }
+}